@@ -19,22 +19,46 @@
 //! Unlike `Arc`, `FinArc<T, F>` implements `DerefMut` to `T`, because each instance of `FinArc` owns
 //! its own copy of `T`
 
+use std::borrow::Borrow;
 use std::cmp::Ordering;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::mem;
 use std::ops::{Deref, DerefMut};
+use std::panic::Location;
 use std::ptr;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+
+/// Shared state behind a family of `FinArc`/`FinWeak` instances.
+///
+/// `live` is the number of outstanding `FinArc` instances; it is tracked separately
+/// from the `Arc`'s own strong count so that `FinWeak::upgrade` and `wait_for_last_drop` can hold
+/// additional `Arc<Inner<F>>` references without being mistaken for live instances.
+struct Inner<F> {
+    marked: AtomicBool,
+    live: AtomicUsize,
+    finalizer: Mutex<Option<F>>,
+    done: Mutex<bool>,
+    done_condvar: Condvar,
+    /// Call sites of every currently live `FinArc` instance, only tracked behind the
+    /// `debug-names` feature so it costs nothing in release builds.
+    #[cfg(feature = "debug-names")]
+    locations: Mutex<Vec<&'static Location<'static>>>,
+}
 
 pub struct FinArc<T, F>
 where
     T: ?Sized,
     F: FnOnce(&mut T),
 {
-    // We will use this field to both don't clone finalizer and to detect when last instance is dropped
-    // Option here as FnOnce accepts `self` by value, and we can take Arc to try to get finalizer if it is possible
-    // Arc<Option<T>> has smaller footprint than Option<Arc<T>> if T can be all-zeros, but FnOnce is not that case
-    inner: Option<Arc<F>>,
+    // Option here as the finalizer is run at most once and we need to be able to take it
+    // out of `FinArc` on drop, which only gives us `&mut self`
+    inner: Option<Arc<Inner<F>>>,
+    #[cfg(feature = "debug-names")]
+    location: &'static Location<'static>,
+    // `data` must stay the last field: `T` may be `?Sized`, and an unsized field must
+    // be the last one in the struct.
     data: T,
 }
 
@@ -42,10 +66,23 @@ impl<T, F> FinArc<T, F>
 where
     F: FnOnce(&mut T),
 {
+    #[track_caller]
     pub fn new(data: T, finalizer: F) -> Self {
+        #[cfg(feature = "debug-names")]
+        let location = Location::caller();
         Self {
-            inner: Some(Arc::new(finalizer)),
+            inner: Some(Arc::new(Inner {
+                marked: AtomicBool::new(false),
+                live: AtomicUsize::new(1),
+                finalizer: Mutex::new(Some(finalizer)),
+                done: Mutex::new(false),
+                done_condvar: Condvar::new(),
+                #[cfg(feature = "debug-names")]
+                locations: Mutex::new(vec![location]),
+            })),
             data,
+            #[cfg(feature = "debug-names")]
+            location,
         }
     }
 
@@ -70,20 +107,291 @@ where
     /// ```
     /// Analogue of `Arc::try_unwrap`
     pub fn try_unwrap(mut this: Self) -> Result<T, Self> {
-        match Arc::try_unwrap(this.inner.take().expect("Finalizer is gone")) {
+        let inner = this.inner.take().expect("Finalizer is gone");
+        // Only take the data if we are the last *live* instance; a `FinWeak` in the
+        // middle of `upgrade` or a thread parked in `wait_for_last_drop` may still hold an
+        // `Arc<Inner<F>>` clone without being a live instance itself.
+        match inner.live.compare_exchange(
+            1,
+            0,
+            AtomicOrdering::AcqRel,
+            AtomicOrdering::Acquire,
+        ) {
             Ok(_) => unsafe {
+                // Mirror what `Drop` does: remove our own call site before forgetting
+                // `this`, so `locations` doesn't end up with a dangling entry for an
+                // instance that no longer exists.
+                #[cfg(feature = "debug-names")]
+                {
+                    let mut locations = inner.locations.lock().unwrap();
+                    if let Some(pos) = locations.iter().position(|l| ptr::eq(*l, this.location)) {
+                        locations.remove(pos);
+                    }
+                }
+                // Live count just reached zero, same as a finalizing drop would observe;
+                // wake up anyone parked in `wait_for_last_drop`.
+                *inner.done.lock().unwrap() = true;
+                inner.done_condvar.notify_all();
                 // we cannot simply move out of FinArc, because it has custom impl of Drop
                 let data = ptr::read(&this.data);
                 // avoid calling Drop, we already dropped finalizer and "moved" data
                 mem::forget(this);
                 Ok(data)
             },
-            Err(arc) => {
-                this.inner = Some(arc);
+            Err(_) => {
+                this.inner = Some(inner);
                 Err(this)
             }
         }
     }
+
+    /// Marks the resource for destruction.
+    ///
+    /// Marking does not by itself finalize anything: it makes [`FinArc::is_marked`]
+    /// return `true`, so other holders can notice that the resource is going away and stop
+    /// issuing new work on it, and it makes [`FinWeak::upgrade`] return `None` from then on.
+    /// The finalizer still only runs once the last live instance is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use finarc::FinArc;
+    ///
+    /// let arc = FinArc::new(5, |_| {});
+    /// assert!(!arc.is_marked());
+    /// arc.mark();
+    /// assert!(arc.is_marked());
+    /// ```
+    pub fn mark(&self) {
+        self.inner
+            .as_ref()
+            .expect("Finalizer is gone")
+            .marked
+            .store(true, AtomicOrdering::Release);
+    }
+
+    /// Returns whether the resource has been [`mark`][FinArc::mark]ed for destruction.
+    pub fn is_marked(&self) -> bool {
+        self.inner
+            .as_ref()
+            .expect("Finalizer is gone")
+            .marked
+            .load(AtomicOrdering::Acquire)
+    }
+
+    /// Consumes this instance and blocks the current thread until every other live
+    /// instance has also been dropped.
+    ///
+    /// Ordinarily the finalizer has already run by the time this call returns. The one
+    /// exception is a sibling instance racing to zero via [`FinArc::try_unwrap`], which
+    /// by design extracts the data without ever running the finalizer; concurrently
+    /// calling `try_unwrap` on a sibling instance while another thread is parked in
+    /// `wait_for_last_drop` is not a supported combination, and in that case this call
+    /// can return before the finalizer has run (or without it ever running at all).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use finarc::FinArc;
+    /// use std::thread;
+    ///
+    /// let arc = FinArc::new(5, |_| {});
+    /// let other = FinArc::clone(&arc);
+    /// let handle = thread::spawn(move || drop(other));
+    ///
+    /// FinArc::wait_for_last_drop(arc);
+    /// handle.join().unwrap();
+    /// ```
+    pub fn wait_for_last_drop(this: Self) {
+        let inner = Arc::clone(this.inner.as_ref().expect("Finalizer is gone"));
+        drop(this);
+
+        let mut done = inner.done.lock().unwrap();
+        while !*done {
+            done = inner.done_condvar.wait(done).unwrap();
+        }
+    }
+
+    /// Returns the call sites of every `FinArc` instance that is currently keeping this
+    /// resource's finalizer from running.
+    ///
+    /// This is a no-op returning an empty `Vec` unless the `debug-names` feature is
+    /// enabled, in which case it is useful to diagnose a finalizer that never fires
+    /// because some thread is holding a clone longer than expected.
+    #[cfg(feature = "debug-names")]
+    pub fn live_callers(&self) -> Vec<&'static Location<'static>> {
+        self.inner
+            .as_ref()
+            .expect("Finalizer is gone")
+            .locations
+            .lock()
+            .unwrap()
+            .clone()
+    }
+
+    /// Returns the call sites of every `FinArc` instance that is currently keeping this
+    /// resource's finalizer from running.
+    ///
+    /// This is a no-op returning an empty `Vec` unless the `debug-names` feature is
+    /// enabled, in which case it is useful to diagnose a finalizer that never fires
+    /// because some thread is holding a clone longer than expected.
+    #[cfg(not(feature = "debug-names"))]
+    pub fn live_callers(&self) -> Vec<&'static Location<'static>> {
+        Vec::new()
+    }
+
+    /// Returns whether two `FinArc`s point to the same underlying resource, i.e. share
+    /// the same finalizer handle.
+    ///
+    /// Note that this compares the finalizer handle, not the data: two `FinArc`s holding
+    /// equal data but backed by different finalizers belong to different resources and
+    /// are not `ptr_eq`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use finarc::FinArc;
+    ///
+    /// let noop: fn(&mut i32) = |_| {};
+    /// let five = FinArc::new(5, noop);
+    /// let same = FinArc::clone(&five);
+    /// let other = FinArc::new(5, noop);
+    ///
+    /// assert!(FinArc::ptr_eq(&five, &same));
+    /// assert!(!FinArc::ptr_eq(&five, &other));
+    /// ```
+    /// Analogue of `Arc::ptr_eq`
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        Arc::ptr_eq(
+            this.inner.as_ref().expect("Finalizer is gone"),
+            other.inner.as_ref().expect("Finalizer is gone"),
+        )
+    }
+
+    /// Returns the number of live `FinArc` instances sharing this resource.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use finarc::FinArc;
+    ///
+    /// let five = FinArc::new(5, |_| {});
+    /// assert_eq!(FinArc::instance_count(&five), 1);
+    ///
+    /// let _same = FinArc::clone(&five);
+    /// assert_eq!(FinArc::instance_count(&five), 2);
+    /// ```
+    /// Analogue of `Arc::strong_count`
+    pub fn instance_count(this: &Self) -> usize {
+        this.inner
+            .as_ref()
+            .expect("Finalizer is gone")
+            .live
+            .load(AtomicOrdering::Acquire)
+    }
+}
+
+impl<T, F> FinArc<T, F>
+where
+    T: Clone,
+    F: FnOnce(&mut T),
+{
+    /// Creates a new [`FinWeak`] pointer to this resource.
+    ///
+    /// The returned `FinWeak` does not keep the resource's finalizer from running; once
+    /// the last `FinArc` instance is dropped and the finalizer has executed,
+    /// [`FinWeak::upgrade`] will return `None`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use finarc::FinArc;
+    ///
+    /// let five = FinArc::new(5, |_| {});
+    /// let weak_five = five.downgrade();
+    ///
+    /// assert_eq!(weak_five.upgrade().map(|a| *a), Some(5));
+    /// ```
+    pub fn downgrade(&self) -> FinWeak<T, F> {
+        FinWeak {
+            inner: Arc::downgrade(self.inner.as_ref().expect("Finalizer is gone")),
+            data: self.data.clone(),
+        }
+    }
+}
+
+/// `FinWeak` is a non-owning reference to a resource managed by [`FinArc`].
+///
+/// It mirrors [`std::sync::Weak`]: holding a `FinWeak` does not keep the resource's
+/// finalizer from running, and [`FinWeak::upgrade`] returns `None` once the last live
+/// `FinArc` instance has been dropped and the finalizer has already run.
+///
+/// A `FinWeak` instance is created with [`FinArc::downgrade`].
+pub struct FinWeak<T, F>
+where
+    F: FnOnce(&mut T),
+{
+    inner: Weak<Inner<F>>,
+    data: T,
+}
+
+impl<T, F> FinWeak<T, F>
+where
+    T: Clone,
+    F: FnOnce(&mut T),
+{
+    /// Attempts to upgrade this `FinWeak` into a [`FinArc`], sharing ownership of the
+    /// same underlying finalizer handle as the `FinArc` it was downgraded from.
+    ///
+    /// Returns `None` if the finalizer has already run, i.e. the last live `FinArc`
+    /// instance was dropped, or if the resource has been [`mark`][FinArc::mark]ed for
+    /// destruction.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use finarc::FinArc;
+    ///
+    /// let five = FinArc::new(5, |_| {});
+    /// let weak_five = five.downgrade();
+    ///
+    /// drop(five);
+    /// assert_eq!(weak_five.upgrade(), None);
+    /// ```
+    #[track_caller]
+    pub fn upgrade(&self) -> Option<FinArc<T, F>> {
+        let inner = self.inner.upgrade()?;
+        if inner.live.load(AtomicOrdering::Acquire) == 0 || inner.marked.load(AtomicOrdering::Acquire) {
+            return None;
+        }
+        // Clone the data before reserving a live slot below: if `T::clone` panics, we
+        // must not have already bumped `live`, or the count would be left permanently
+        // too high with no instance to ever bring it back down.
+        let data = self.data.clone();
+        loop {
+            let live = inner.live.load(AtomicOrdering::Acquire);
+            if live == 0 || inner.marked.load(AtomicOrdering::Acquire) {
+                return None;
+            }
+            if inner
+                .live
+                .compare_exchange_weak(live, live + 1, AtomicOrdering::AcqRel, AtomicOrdering::Acquire)
+                .is_ok()
+            {
+                break;
+            }
+        }
+        #[cfg(feature = "debug-names")]
+        let location = Location::caller();
+        #[cfg(feature = "debug-names")]
+        inner.locations.lock().unwrap().push(location);
+        Some(FinArc {
+            inner: Some(inner),
+            data,
+            #[cfg(feature = "debug-names")]
+            location,
+        })
+    }
 }
 
 impl<T, F> Deref for FinArc<T, F>
@@ -113,10 +421,24 @@ where
     T: Clone,
     F: FnOnce(&mut T),
 {
+    #[track_caller]
     fn clone(&self) -> Self {
+        // Clone the data first: if `T::clone` panics, we must not have already
+        // bumped `live` (or pushed a location), or the counts would be left
+        // permanently too high with no instance to ever bring them back down.
+        let data = self.data.clone();
+        let inner = self.inner.clone();
+        let inner_ref = inner.as_ref().expect("Finalizer is gone");
+        inner_ref.live.fetch_add(1, AtomicOrdering::AcqRel);
+        #[cfg(feature = "debug-names")]
+        let location = Location::caller();
+        #[cfg(feature = "debug-names")]
+        inner_ref.locations.lock().unwrap().push(location);
         Self {
-            inner: self.inner.clone(),
-            data: self.data.clone(),
+            inner,
+            data,
+            #[cfg(feature = "debug-names")]
+            location,
         }
     }
 }
@@ -127,10 +449,22 @@ where
     F: FnOnce(&mut T),
 {
     fn drop(&mut self) {
-        // Here we both checked that it is the last instance and got callback from it, double win!
-        // If it is not last instance, Err will return Arc back and it will be dropped normally, without calling finalizer
-        if let Ok(f) = Arc::try_unwrap(self.inner.take().expect("Finalizer is gone")) {
-            (f)(&mut self.data);
+        let inner = self.inner.take().expect("Finalizer is gone");
+        #[cfg(feature = "debug-names")]
+        {
+            let mut locations = inner.locations.lock().unwrap();
+            if let Some(pos) = locations.iter().position(|l| ptr::eq(*l, self.location)) {
+                locations.remove(pos);
+            }
+        }
+        // Whichever instance observes the live count dropping to zero is the one that
+        // runs the finalizer, and it notifies anyone parked in `wait_for_last_drop`.
+        if inner.live.fetch_sub(1, AtomicOrdering::AcqRel) == 1 {
+            if let Some(f) = inner.finalizer.lock().unwrap().take() {
+                f(&mut self.data);
+            }
+            *inner.done.lock().unwrap() = true;
+            inner.done_condvar.notify_all();
         }
     }
 }
@@ -315,10 +649,31 @@ impl<T: ?Sized + fmt::Debug, F: FnOnce(&mut T)> fmt::Debug for FinArc<T, F> {
     }
 }
 
+/// We ignore the finalizer when hashing a `FinArc`, consistent with the `PartialEq`
+/// impl above: `FinArc`s that hash differently must also compare unequal.
+impl<T: ?Sized + Hash, F: FnOnce(&mut T)> Hash for FinArc<T, F> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+impl<T: ?Sized, F: FnOnce(&mut T)> Borrow<T> for FinArc<T, F> {
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
+impl<T: ?Sized, F: FnOnce(&mut T)> AsRef<T> for FinArc<T, F> {
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::FinArc;
     use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
 
     #[test]
     fn test_finalizer_is_called_once_last_clone_is_dropped() {
@@ -362,4 +717,212 @@ mod test {
         drop(arc_clone);
         assert_eq!(close_counter.load(Ordering::SeqCst), 1);
     }
+
+    #[test]
+    fn weak_upgrade_succeeds_while_strong_instance_is_alive() {
+        let arc = FinArc::new(5, |_| {});
+        let weak = arc.downgrade();
+
+        assert_eq!(weak.upgrade().map(|a| *a), Some(5));
+    }
+
+    #[test]
+    fn weak_upgrade_fails_once_finalizer_has_run() {
+        let arc = FinArc::new(5, |_| {});
+        let weak = arc.downgrade();
+
+        drop(arc);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn mark_does_not_run_finalizer_while_instances_are_alive() {
+        let close_counter = AtomicUsize::new(0);
+        let arc = FinArc::new((), |_| {
+            close_counter.fetch_add(1, Ordering::SeqCst);
+        });
+        let clone = arc.clone();
+
+        arc.mark();
+        assert!(arc.is_marked());
+        assert!(clone.is_marked());
+        assert_eq!(close_counter.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn weak_upgrade_fails_once_marked() {
+        let arc = FinArc::new(5, |_| {});
+        let weak = arc.downgrade();
+
+        arc.mark();
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn wait_for_last_drop_blocks_until_other_clone_is_dropped() {
+        let close_counter = Arc::new(AtomicUsize::new(0));
+        let close_counter_clone = close_counter.clone();
+        let arc = FinArc::new((), move |_| {
+            close_counter_clone.fetch_add(1, Ordering::SeqCst);
+        });
+        let other = arc.clone();
+
+        let handle = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            drop(other);
+        });
+
+        FinArc::wait_for_last_drop(arc);
+        assert_eq!(close_counter.load(Ordering::SeqCst), 1);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn finalizer_runs_exactly_once_under_concurrent_clone_and_drop() {
+        use std::sync::Barrier;
+
+        const THREADS: usize = 32;
+
+        for _ in 0..50 {
+            let close_counter = Arc::new(AtomicUsize::new(0));
+            let close_counter_clone = close_counter.clone();
+            let arc = FinArc::new((), move |_| {
+                close_counter_clone.fetch_add(1, Ordering::SeqCst);
+            });
+            // +1 for the main thread, which races the spawned threads to drop `arc` too.
+            let barrier = Arc::new(Barrier::new(THREADS + 1));
+
+            let handles: Vec<_> = (0..THREADS)
+                .map(|_| {
+                    let clone = arc.clone();
+                    let barrier = barrier.clone();
+                    std::thread::spawn(move || {
+                        // Fan out further clones/drops on this thread before releasing
+                        // its own handle, so many instances are being cloned and
+                        // dropped concurrently across the whole family.
+                        let nested = clone.clone();
+                        barrier.wait();
+                        drop(nested);
+                        drop(clone);
+                    })
+                })
+                .collect();
+
+            barrier.wait();
+            drop(arc);
+
+            for handle in handles {
+                handle.join().unwrap();
+            }
+
+            assert_eq!(close_counter.load(Ordering::SeqCst), 1);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "debug-names")]
+    fn live_callers_tracks_and_forgets_call_sites() {
+        let arc = FinArc::new(5, |_| {});
+        assert_eq!(arc.live_callers().len(), 1);
+
+        let clone = arc.clone();
+        assert_eq!(arc.live_callers().len(), 2);
+
+        drop(clone);
+        assert_eq!(arc.live_callers().len(), 1);
+    }
+
+    #[test]
+    #[cfg(not(feature = "debug-names"))]
+    fn live_callers_is_empty_without_the_feature() {
+        let arc = FinArc::new(5, |_| {});
+        assert!(arc.live_callers().is_empty());
+    }
+
+    #[test]
+    fn ptr_eq_distinguishes_resources_with_equal_data() {
+        let noop: fn(&mut i32) = |_| {};
+        let arc = FinArc::new(5, noop);
+        let clone = arc.clone();
+        let other = FinArc::new(5, noop);
+
+        assert!(FinArc::ptr_eq(&arc, &clone));
+        assert!(!FinArc::ptr_eq(&arc, &other));
+    }
+
+    #[test]
+    fn instance_count_tracks_live_clones() {
+        let arc = FinArc::new(5, |_| {});
+        assert_eq!(FinArc::instance_count(&arc), 1);
+
+        let clone = arc.clone();
+        assert_eq!(FinArc::instance_count(&arc), 2);
+
+        drop(clone);
+        assert_eq!(FinArc::instance_count(&arc), 1);
+    }
+
+    #[test]
+    fn clone_panicking_mid_clone_does_not_corrupt_instance_count() {
+        struct PanicsOnClone;
+        impl Clone for PanicsOnClone {
+            fn clone(&self) -> Self {
+                panic!("clone is not allowed to succeed")
+            }
+        }
+
+        let arc = FinArc::new(PanicsOnClone, |_| {});
+        assert_eq!(FinArc::instance_count(&arc), 1);
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| arc.clone()));
+        assert!(result.is_err());
+        assert_eq!(FinArc::instance_count(&arc), 1);
+    }
+
+    #[test]
+    fn weak_upgrade_panicking_mid_clone_does_not_corrupt_instance_count() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        struct PanicsOnSecondClone(Rc<Cell<u32>>);
+        impl Clone for PanicsOnSecondClone {
+            fn clone(&self) -> Self {
+                let calls = self.0.get() + 1;
+                self.0.set(calls);
+                if calls >= 2 {
+                    panic!("clone is not allowed to succeed a second time");
+                }
+                PanicsOnSecondClone(self.0.clone())
+            }
+        }
+
+        let arc = FinArc::new(PanicsOnSecondClone(Rc::new(Cell::new(0))), |_| {});
+        let weak = arc.downgrade();
+        assert_eq!(FinArc::instance_count(&arc), 1);
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| weak.upgrade()));
+        assert!(result.is_err());
+        assert_eq!(FinArc::instance_count(&arc), 1);
+    }
+
+    #[test]
+    #[allow(clippy::mutable_key_type)] // the finalizer's interior mutability doesn't affect Hash/Eq, which only look at the data
+    fn finarc_can_be_used_as_a_hash_set_key() {
+        use std::collections::HashSet;
+
+        let noop: fn(&mut i32) = |_| {};
+        let mut set = HashSet::new();
+        set.insert(FinArc::new(5, noop));
+        assert!(!set.insert(FinArc::new(5, noop)));
+        assert!(set.insert(FinArc::new(6, noop)));
+    }
+
+    #[test]
+    fn finarc_borrows_and_as_refs_to_inner_data() {
+        use std::borrow::Borrow;
+
+        let arc = FinArc::new(5, |_| {});
+        assert_eq!(Borrow::<i32>::borrow(&arc), &5);
+        assert_eq!(AsRef::<i32>::as_ref(&arc), &5);
+    }
 }